@@ -42,30 +42,222 @@
 //! }
 //! ```
 use std::any::TypeId;
+use std::fmt::Write as _;
+use std::sync::Mutex;
 
-use opentelemetry::trace::{TraceContextExt, Tracer};
+use opentelemetry::baggage::BaggageExt;
+use opentelemetry::trace::{SamplingDecision, TraceContextExt, TraceFlags, Tracer};
 use tracing::{
-    field::FieldSet,
+    field::{Field, FieldSet},
     metadata::LevelFilter,
     span::{Attributes, Record},
     subscriber::Interest,
     Event, Id, Metadata, Span, Subscriber, Value,
 };
-use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt, PreSampledTracer};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt, OtelData, PreSampledTracer};
 use tracing_subscriber::{
-    fmt::{FormatEvent, FormatFields, Layer as FmtLayer, MakeWriter},
-    layer::{Context, Layered},
+    filter::Filtered,
+    fmt::{FormatEvent, FormatFields, FormattedFields, Layer as FmtLayer, MakeWriter},
+    layer::{Context, Filter, Layered},
     registry::LookupSpan,
     Layer,
 };
 
-pub struct OpenTelemetryFmtLayerBuilder<S, T1, N2, E2, W2> {
+/// Which fields from the current `SpanContext` (and its parent) should be
+/// recorded on `FmtLayer`, and under what name.
+///
+/// A field left as `None` is simply not recorded. The default matches the
+/// original, hard-coded behaviour of this crate: only `trace.id` and
+/// `span.id` are emitted.
+#[derive(Debug, Clone)]
+pub struct FieldConfig {
+    pub trace_id: Option<&'static str>,
+    pub span_id: Option<&'static str>,
+    /// The sampling flags byte (e.g. `01` when sampled). Depends on
+    /// OpenTelemetry's sampling decision, which usually isn't made for a
+    /// span until it gets a child or closes; until then this field (and
+    /// [`Self::sampled`]/[`Self::traceparent`]) is simply omitted from that
+    /// log line rather than guessing "unsampled".
+    pub trace_flags: Option<&'static str>,
+    /// Whether the span is sampled, as a `bool`. See the [`Self::trace_flags`]
+    /// caveat: omitted until the sampling decision is known.
+    pub sampled: Option<&'static str>,
+    pub parent_span_id: Option<&'static str>,
+    /// Whether `span_context().is_remote()` reports this span as having a
+    /// remote parent (i.e. its trace context was propagated in from another
+    /// service, rather than created locally).
+    pub is_remote: Option<&'static str>,
+    /// Emits a single W3C Trace Context `traceparent` field, formatted as
+    /// `00-{trace_id}-{span_id}-{flags}`, instead of (or alongside) the
+    /// separate id fields above. Shares the same sampling caveat as
+    /// [`Self::trace_flags`]: omitted until the flags byte is known.
+    pub traceparent: Option<&'static str>,
+}
+
+/// Controls when the fields configured by [`FieldConfig`] are resolved and
+/// recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InjectionMode {
+    /// Resolve and record the configured fields once, when a span is
+    /// entered. This is the original behaviour: every log line inside the
+    /// span picks them up because `FmtLayer` re-reads recorded span fields
+    /// when formatting an event.
+    #[default]
+    OnEnter,
+    /// Resolve the configured fields for the span an event actually belongs
+    /// to (via [`Context::event_span`]), and record them immediately before
+    /// that event is formatted. Unlike `OnEnter`, this keeps every log line
+    /// correct even if the thread-local "current" span at the time of the
+    /// call doesn't match the span the event was emitted from, or if the
+    /// span's id changed after it was entered.
+    OnEvent,
+}
+
+impl Default for FieldConfig {
+    fn default() -> Self {
+        Self {
+            trace_id: Some("trace.id"),
+            span_id: Some("span.id"),
+            trace_flags: None,
+            sampled: None,
+            parent_span_id: None,
+            is_remote: None,
+            traceparent: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ContextField {
+    TraceId,
+    SpanId,
+    TraceFlags,
+    Sampled,
+    ParentSpanId,
+    IsRemote,
+    Traceparent,
+}
+
+/// Renders `bytes` as a lowercase, zero-padded hex string, regardless of how
+/// the opentelemetry id types choose to implement `Display`.
+fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(s, "{:02x}", byte).expect("writing to a String cannot fail");
+    }
+    s
+}
+
+/// Builds the W3C Trace Context `traceparent` string for `span_context`.
+fn traceparent(span_context: &opentelemetry::trace::SpanContext) -> String {
+    format!(
+        "00-{}-{}-{:02x}",
+        to_hex(&span_context.trace_id().to_bytes()),
+        to_hex(&span_context.span_id().to_bytes()),
+        u8::from(span_context.trace_flags().is_sampled())
+    )
+}
+
+/// Flattens a `FieldConfig` into the parallel `(names, fields)` vectors used
+/// to build a `FieldSet` each time the fields are recorded.
+fn context_fields(field_config: &FieldConfig) -> (Vec<&'static str>, Vec<ContextField>) {
+    let mut names = Vec::with_capacity(7);
+    let mut fields = Vec::with_capacity(7);
+    if let Some(name) = field_config.trace_id {
+        names.push(name);
+        fields.push(ContextField::TraceId);
+    }
+    if let Some(name) = field_config.span_id {
+        names.push(name);
+        fields.push(ContextField::SpanId);
+    }
+    if let Some(name) = field_config.trace_flags {
+        names.push(name);
+        fields.push(ContextField::TraceFlags);
+    }
+    if let Some(name) = field_config.sampled {
+        names.push(name);
+        fields.push(ContextField::Sampled);
+    }
+    if let Some(name) = field_config.parent_span_id {
+        names.push(name);
+        fields.push(ContextField::ParentSpanId);
+    }
+    if let Some(name) = field_config.is_remote {
+        names.push(name);
+        fields.push(ContextField::IsRemote);
+    }
+    if let Some(name) = field_config.traceparent {
+        names.push(name);
+        fields.push(ContextField::Traceparent);
+    }
+    (names, fields)
+}
+
+/// Controls whether, and how, OpenTelemetry `Baggage` entries are recorded
+/// as fmt fields.
+///
+/// Baggage keys are only known at runtime, so (unlike `FieldConfig`) this
+/// has no fixed field names: every baggage entry present on `on_enter` is
+/// recorded, optionally narrowed by `allow_list` and renamed with `prefix`.
+#[derive(Debug, Clone, Default)]
+pub struct BaggageConfig {
+    pub enabled: bool,
+    pub prefix: Option<&'static str>,
+    pub allow_list: Option<Vec<&'static str>>,
+}
+
+/// Field names under which the OpenTelemetry exception semantic conventions
+/// are re-recorded, matching the attributes `tracing-opentelemetry` already
+/// attaches to the span: `exception.message` (the top-level `Display`) and
+/// `exception.stacktrace` (the newline-joined `source()` chain).
+const EXCEPTION_FIELD_NAMES: &[&str; 2] = &["exception.message", "exception.stacktrace"];
+
+/// Extracts the first `std::error::Error` value recorded on an event,
+/// rendering it and its `source()` chain the same way
+/// `tracing-opentelemetry` does for the OTel exception attributes.
+#[derive(Default)]
+struct ExceptionVisitor {
+    fields: Option<(String, String)>,
+}
+
+impl ExceptionVisitor {
+    fn message_and_stacktrace(&mut self) -> Option<(String, String)> {
+        self.fields.take()
+    }
+}
+
+impl tracing::field::Visit for ExceptionVisitor {
+    fn record_error(&mut self, _field: &Field, value: &(dyn std::error::Error + 'static)) {
+        if self.fields.is_none() {
+            self.fields = Some((value.to_string(), exception_stacktrace(value)));
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+fn exception_stacktrace(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut chain = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(error) = source {
+        chain.push(error.to_string());
+        source = error.source();
+    }
+    chain.join("\n")
+}
+
+pub struct OpenTelemetryFmtLayerBuilder<S, T1, N2, E2, W2, F> {
     opentelemetry_layer: OpenTelemetryLayer<S, T1>,
     fmt_layer: FmtLayer<S, N2, E2, W2>,
-    field_names: &'static [&'static str; 2],
+    field_config: FieldConfig,
+    injection_mode: InjectionMode,
+    baggage: BaggageConfig,
+    exception_fields: bool,
+    fmt_filter: F,
 }
 
-impl<S, T1, N2, E2, W2> OpenTelemetryFmtLayerBuilder<S, T1, N2, E2, W2> {
+impl<S, T1, N2, E2, W2> OpenTelemetryFmtLayerBuilder<S, T1, N2, E2, W2, LevelFilter> {
     pub fn new(
         opentelemetry_layer: OpenTelemetryLayer<S, T1>,
         fmt_layer: FmtLayer<S, N2, E2, W2>,
@@ -73,43 +265,426 @@ impl<S, T1, N2, E2, W2> OpenTelemetryFmtLayerBuilder<S, T1, N2, E2, W2> {
         Self {
             opentelemetry_layer,
             fmt_layer,
-            field_names: &["trace.id", "span.id"],
+            field_config: FieldConfig::default(),
+            injection_mode: InjectionMode::default(),
+            baggage: BaggageConfig::default(),
+            exception_fields: false,
+            // Unfiltered by default: every span/event the `OpenTelemetryLayer`
+            // sees, the fmt layer sees too, matching the original behaviour.
+            fmt_filter: LevelFilter::TRACE,
         }
     }
+}
 
-    pub fn with_field_names(mut self, field_names: &'static [&'static str; 2]) -> Self {
-        self.field_names = field_names;
+impl<S, T1, N2, E2, W2, F> OpenTelemetryFmtLayerBuilder<S, T1, N2, E2, W2, F> {
+    pub fn with_field_config(mut self, field_config: FieldConfig) -> Self {
+        self.field_config = field_config;
         self
     }
+
+    /// Chooses when the fields from [`Self::with_field_config`] are
+    /// resolved and recorded. Defaults to [`InjectionMode::OnEnter`].
+    pub fn with_injection_mode(mut self, injection_mode: InjectionMode) -> Self {
+        self.injection_mode = injection_mode;
+        self
+    }
+
+    /// Records OpenTelemetry baggage entries (see
+    /// [`opentelemetry::baggage::BaggageExt::baggage`]) as
+    /// fmt fields. Narrow which keys are recorded with
+    /// [`Self::with_baggage_keys`], or rename them with
+    /// [`Self::with_baggage_prefix`].
+    ///
+    /// Each distinct baggage *key* name seen is leaked once to satisfy
+    /// `tracing`'s `'static` field-name requirement (see
+    /// `intern_field_names`); this is bounded by the number of distinct keys
+    /// an application uses, not the number of baggage entries. If baggage
+    /// comes from a propagated, caller-controlled source (e.g. an incoming
+    /// W3C `baggage` header) where a caller could vary key names per
+    /// request, that bound disappears and this becomes an unbounded,
+    /// process-lifetime memory leak. Use [`Self::with_baggage_keys`] to pin
+    /// the allowed key set whenever baggage crosses an untrusted boundary.
+    pub fn with_baggage(mut self, enabled: bool) -> Self {
+        self.baggage.enabled = enabled;
+        self
+    }
+
+    /// Prefixes every recorded baggage field name with `prefix`, e.g.
+    /// `"baggage."` turns the `tenant` key into the `baggage.tenant` field.
+    pub fn with_baggage_prefix(mut self, prefix: &'static str) -> Self {
+        self.baggage.prefix = Some(prefix);
+        self
+    }
+
+    /// Restricts recorded baggage entries to `keys`. Without this, every
+    /// baggage entry is recorded.
+    pub fn with_baggage_keys(mut self, keys: Vec<&'static str>) -> Self {
+        self.baggage.allow_list = Some(keys);
+        self
+    }
+
+    /// Surfaces the OpenTelemetry exception semantic-convention fields
+    /// (`exception.message`, `exception.stacktrace`) on the console output
+    /// whenever an event records a `std::error::Error` value, mirroring the
+    /// attributes `tracing-opentelemetry` already attaches to the span.
+    pub fn with_exception_fields(mut self, enabled: bool) -> Self {
+        self.exception_fields = enabled;
+        self
+    }
+
+    /// Gives the fmt half of the layer its own filter, independent of the
+    /// `OpenTelemetryLayer`, mirroring `tracing_opentelemetry`'s
+    /// `MetricsLayer::with_filter`. This lets every span reach the
+    /// OpenTelemetry exporter while only, say, `INFO`-and-above is printed
+    /// to the console.
+    pub fn with_fmt_filter<F2>(
+        self,
+        fmt_filter: F2,
+    ) -> OpenTelemetryFmtLayerBuilder<S, T1, N2, E2, W2, F2> {
+        let Self {
+            opentelemetry_layer,
+            fmt_layer,
+            field_config,
+            injection_mode,
+            baggage,
+            exception_fields,
+            fmt_filter: _,
+        } = self;
+        OpenTelemetryFmtLayerBuilder {
+            opentelemetry_layer,
+            fmt_layer,
+            field_config,
+            injection_mode,
+            baggage,
+            exception_fields,
+            fmt_filter,
+        }
+    }
 }
 
-impl<S, T1, N2, E2, W2> OpenTelemetryFmtLayerBuilder<S, T1, N2, E2, W2>
+/// The layer type returned by [`OpenTelemetryFmtLayerBuilder::build`]: an
+/// `OpenTelemetryLayer` feeding an independently-filtered
+/// [`OpenTelemetryFmtLayer`], mirroring `tracing_opentelemetry`'s
+/// `MetricsLayer`/`Filtered` composition.
+pub type OpenTelemetryFmtLayered<S, T1, N2, E2, W2, F> =
+    Layered<Filtered<OpenTelemetryFmtLayer<S, N2, E2, W2>, F, S>, OpenTelemetryLayer<S, T1>, S>;
+
+impl<S, T1, N2, E2, W2, F> OpenTelemetryFmtLayerBuilder<S, T1, N2, E2, W2, F>
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
     T1: Tracer + PreSampledTracer + 'static,
     N2: for<'writer> FormatFields<'writer> + 'static,
     E2: FormatEvent<S, N2> + 'static,
     W2: for<'writer> MakeWriter<'writer> + 'static,
+    F: Filter<S> + 'static,
 {
-    pub fn build(
-        self,
-    ) -> Layered<OpenTelemetryFmtLayer<S, N2, E2, W2>, OpenTelemetryLayer<S, T1>, S> {
+    pub fn build(self) -> OpenTelemetryFmtLayered<S, T1, N2, E2, W2, F> {
         let Self {
             opentelemetry_layer,
             fmt_layer,
-            field_names,
+            field_config,
+            injection_mode,
+            baggage,
+            exception_fields,
+            fmt_filter,
         } = self;
+        let (field_names, context_fields) = context_fields(&field_config);
+        // `FieldSet::new` requires a `&'static [&'static str]`, but the set of
+        // names is only known once the config is flattened here. The builder
+        // runs once per layer, so leaking this small, one-time allocation is
+        // cheaper than re-deriving it on every `on_enter`.
+        let field_names: &'static [&'static str] = Box::leak(field_names.into_boxed_slice());
         let opentelemetry_fmt_layer = OpenTelemetryFmtLayer {
             fmt_layer,
             field_names,
+            context_fields,
+            injection_mode,
+            baggage,
+            exception_fields,
         };
-        opentelemetry_layer.and_then(opentelemetry_fmt_layer)
+        opentelemetry_layer.and_then(opentelemetry_fmt_layer.with_filter(fmt_filter))
     }
 }
 
 pub struct OpenTelemetryFmtLayer<S, N2, E2, W2> {
     fmt_layer: FmtLayer<S, N2, E2, W2>,
-    field_names: &'static [&'static str; 2],
+    field_names: &'static [&'static str],
+    context_fields: Vec<ContextField>,
+    injection_mode: InjectionMode,
+    baggage: BaggageConfig,
+    exception_fields: bool,
+}
+
+/// The resolved string value of each of a layer's `context_fields`, cached
+/// in a span's extensions so repeated `on_event` calls don't have to
+/// re-walk `OtelData` every time.
+///
+/// A field is `None` when it depends on the sampling decision (see
+/// [`ContextField::TraceFlags`]/`Sampled`/`Traceparent`) and that decision
+/// isn't known yet; such values are never cached (see
+/// [`OpenTelemetryFmtLayer::cached_context_fields`]) so they're re-resolved
+/// until they become available.
+struct CachedContextFields(Vec<Option<String>>);
+
+impl<S, N2, E2, W2> OpenTelemetryFmtLayer<S, N2, E2, W2>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N2: for<'writer> FormatFields<'writer> + 'static,
+    E2: FormatEvent<S, N2> + 'static,
+    W2: for<'writer> MakeWriter<'writer> + 'static,
+{
+    /// Looks up the OpenTelemetry span id of `id`'s parent span, if any.
+    fn parent_span_id(&self, id: &Id, ctx: &Context<'_, S>) -> Option<String> {
+        let span = ctx.span(id)?;
+        let parent = span.parent()?;
+        let extensions = parent.extensions();
+        let otel_data = extensions.get::<OtelData>()?;
+        otel_data.builder.span_id.map(|span_id| span_id.to_string())
+    }
+
+    /// Renders `self.context_fields` against `span_context`. Fields that
+    /// depend on the sampling decision are `None` when `sampling_decided` is
+    /// `false`, rather than guessing: `tracing-opentelemetry` only populates
+    /// `SpanBuilder::sampling_result` once the sampler has actually run
+    /// (typically when a child span is created or this span closes), so
+    /// guessing "unsampled" for every span in between would make every
+    /// sampled trace look dropped.
+    fn context_field_values(
+        &self,
+        id: &Id,
+        ctx: &Context<'_, S>,
+        span_context: &opentelemetry::trace::SpanContext,
+        sampling_decided: bool,
+    ) -> Vec<Option<String>> {
+        self.context_fields
+            .iter()
+            .map(|field| match field {
+                ContextField::TraceId => Some(span_context.trace_id().to_string()),
+                ContextField::SpanId => Some(span_context.span_id().to_string()),
+                ContextField::TraceFlags => sampling_decided
+                    .then(|| format!("{:02x}", span_context.trace_flags().to_u8())),
+                ContextField::Sampled => {
+                    sampling_decided.then(|| span_context.trace_flags().is_sampled().to_string())
+                }
+                ContextField::ParentSpanId => {
+                    Some(self.parent_span_id(id, ctx).unwrap_or_default())
+                }
+                ContextField::IsRemote => Some(span_context.is_remote().to_string()),
+                ContextField::Traceparent => sampling_decided.then(|| traceparent(span_context)),
+            })
+            .collect()
+    }
+
+    /// Resolves `self.context_fields` for `id` from its own stored
+    /// `OtelData`, rather than from the thread-local "current" span. This
+    /// is what lets [`InjectionMode::OnEvent`] attribute the right ids to
+    /// an event even when it isn't emitted from the current span.
+    fn resolve_context_fields(&self, id: &Id, ctx: &Context<'_, S>) -> Option<Vec<Option<String>>> {
+        if self.context_fields.is_empty() {
+            return None;
+        }
+        let span = ctx.span(id)?;
+        let extensions = span.extensions();
+        let otel_data = extensions.get::<OtelData>()?;
+        let span_id = otel_data
+            .builder
+            .span_id
+            .unwrap_or(opentelemetry::trace::SpanId::INVALID);
+        let parent_span_context = otel_data.parent_cx.span().span_context().clone();
+        let trace_id = otel_data
+            .builder
+            .trace_id
+            .unwrap_or_else(|| parent_span_context.trace_id());
+        // `SpanBuilder` has no `trace_flags` field; the sampled bit lives on
+        // its `sampling_result` instead, once the sampler has run. Until
+        // then (the common case for a leaf span with no children yet), the
+        // parent's flags are used: with a valid parent they count as the
+        // decision and are emitted; without one, the fields derived from
+        // them are gated on `sampling_decided` below and aren't emitted.
+        let sampling_decided =
+            otel_data.builder.sampling_result.is_some() || parent_span_context.is_valid();
+        let trace_flags = otel_data
+            .builder
+            .sampling_result
+            .as_ref()
+            .map(|sampling_result| match sampling_result.decision {
+                SamplingDecision::RecordAndSample => TraceFlags::SAMPLED,
+                _ => TraceFlags::default(),
+            })
+            .unwrap_or_else(|| parent_span_context.trace_flags());
+        // A span's own context is only ever "remote" by inheriting that
+        // status from a propagated parent; there's no other signal for it
+        // once the span is already local.
+        let is_remote = parent_span_context.is_remote();
+        let span_context = opentelemetry::trace::SpanContext::new(
+            trace_id,
+            span_id,
+            trace_flags,
+            is_remote,
+            Default::default(),
+        );
+        drop(extensions);
+        Some(self.context_field_values(id, ctx, &span_context, sampling_decided))
+    }
+
+    /// Returns `id`'s cached context field values, resolving and caching
+    /// them first if this is the first time they're needed.
+    ///
+    /// Values aren't cached while any of them are still `None` (the sampling
+    /// decision isn't known yet), so later calls keep re-resolving until a
+    /// real decision is available instead of permanently caching a guess.
+    fn cached_context_fields(&self, id: &Id, ctx: &Context<'_, S>) -> Option<Vec<Option<String>>> {
+        let span = ctx.span(id)?;
+        if let Some(cached) = span.extensions().get::<CachedContextFields>() {
+            return Some(cached.0.clone());
+        }
+        drop(span);
+        let values = self.resolve_context_fields(id, ctx)?;
+        if values.iter().all(Option::is_some) {
+            if let Some(span) = ctx.span(id) {
+                span.extensions_mut()
+                    .insert(CachedContextFields(values.clone()));
+            }
+        }
+        Some(values)
+    }
+
+    /// Records `values` under `self.field_names` onto `id`, the same way
+    /// regardless of which [`InjectionMode`] triggered it. A `None` value is
+    /// simply not recorded this time around.
+    fn record_context_fields(&self, id: &Id, ctx: &Context<'_, S>, values: &[Option<String>]) {
+        if self.field_names.is_empty() {
+            return;
+        }
+        let Some(metadata) = ctx.metadata(id) else {
+            return;
+        };
+        let field_set = FieldSet::new(self.field_names, metadata.callsite());
+        // `FieldSet::value_set` only accepts fixed-size arrays, and the
+        // number of configured fields isn't known at compile time, so each
+        // field is recorded one at a time with a single-element array, the
+        // same way `record_baggage` does below.
+        for (field, value) in field_set.iter().zip(values.iter()) {
+            let Some(value) = value else { continue };
+            let values = [(&field, Some(value as &dyn Value))];
+            let value_set = field_set.value_set(&values);
+            let record = Record::new(&value_set);
+            self.fmt_layer.on_record(id, &record, ctx.clone());
+        }
+    }
+
+    /// Records each entry of `otel_cx`'s baggage as its own fmt field.
+    ///
+    /// Baggage keys aren't known until runtime, so unlike the fixed
+    /// `context_fields` above, each entry gets its own single-field
+    /// `FieldSet`/`Record` built off the entering span's callsite.
+    fn record_baggage(&self, id: &Id, otel_cx: &opentelemetry::Context, ctx: &Context<'_, S>) {
+        let callsite = match ctx.metadata(id) {
+            Some(metadata) => metadata.callsite(),
+            None => return,
+        };
+        for (key, (value, _metadata)) in otel_cx.baggage().iter() {
+            let key = key.as_str();
+            if let Some(allow_list) = &self.baggage.allow_list {
+                if !allow_list.contains(&key) {
+                    continue;
+                }
+            }
+            let field_name = match self.baggage.prefix {
+                Some(prefix) => format!("{prefix}{key}"),
+                None => key.to_string(),
+            };
+            let field_names = intern_field_names(field_name);
+            let field_set = FieldSet::new(field_names, callsite.clone());
+            let field = field_set
+                .iter()
+                .next()
+                .expect("baggage field not found, this is a bug");
+            let value = value.to_string();
+            let values = [(&field, Some(&value as &dyn Value))];
+            let value_set = field_set.value_set(&values);
+            let record = Record::new(&value_set);
+            self.fmt_layer.on_record(id, &record, ctx.clone());
+        }
+    }
+
+    /// If `event` recorded a `std::error::Error`, re-records its message and
+    /// source chain as `exception.message`/`exception.stacktrace` fields on
+    /// the currently entered span, just long enough for the caller to format
+    /// this one event.
+    ///
+    /// `on_record` persists onto the span's formatted fields for the rest of
+    /// the span's life, but these fields belong to this one event, not the
+    /// span. So rather than leaving them recorded, this snapshots whatever
+    /// was there beforehand and returns it (alongside the span id) for
+    /// [`Self::restore_formatted_fields`] to put back once `fmt_layer` has
+    /// formatted the event these fields belong to.
+    fn record_exception_fields(
+        &self,
+        event: &Event<'_>,
+        ctx: &Context<'_, S>,
+    ) -> Option<(Id, Option<String>)> {
+        let mut visitor = ExceptionVisitor::default();
+        event.record(&mut visitor);
+        let (message, stacktrace) = visitor.message_and_stacktrace()?;
+        let id = ctx.current_span().id().cloned()?;
+        let metadata = ctx.metadata(&id)?;
+        let field_set = FieldSet::new(EXCEPTION_FIELD_NAMES, metadata.callsite());
+        let fields: Vec<Field> = field_set.iter().collect();
+        let values = [
+            (&fields[0], Some(&message as &dyn Value)),
+            (&fields[1], Some(&stacktrace as &dyn Value)),
+        ];
+        let value_set = field_set.value_set(&values);
+        let record = Record::new(&value_set);
+        let previous = ctx.span(&id).and_then(|span| {
+            span.extensions()
+                .get::<FormattedFields<N2>>()
+                .map(|fields| fields.fields.clone())
+        });
+        self.fmt_layer.on_record(&id, &record, ctx.clone());
+        Some((id, previous))
+    }
+
+    /// Restores `id`'s formatted span fields to `previous` (or removes them
+    /// if there was nothing recorded before), undoing fields recorded for a
+    /// single event: the `exception.*` fields from
+    /// [`Self::record_exception_fields`], or the context fields recorded in
+    /// [`InjectionMode::OnEvent`].
+    fn restore_formatted_fields(&self, id: &Id, previous: Option<String>, ctx: &Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        match previous {
+            Some(previous) => {
+                if let Some(fields) = extensions.get_mut::<FormattedFields<N2>>() {
+                    fields.fields = previous;
+                }
+            }
+            None => {
+                extensions.remove::<FormattedFields<N2>>();
+            }
+        }
+    }
+}
+
+/// Interns a single-field `&'static [&'static str]` for `name`, reusing a
+/// previously leaked allocation if this name has been seen before.
+///
+/// Baggage field names are only known at runtime, but `FieldSet::new` needs
+/// `'static` names. Leaking is bounded by the number of *distinct* baggage
+/// keys an application ever uses, which is small and fixed in practice.
+fn intern_field_names(name: String) -> &'static [&'static str] {
+    static INTERNED: Mutex<Vec<(String, &'static [&'static str])>> = Mutex::new(Vec::new());
+    let mut interned = INTERNED.lock().expect("lock poisoned");
+    if let Some((_, field_names)) = interned.iter().find(|(cached, _)| *cached == name) {
+        return field_names;
+    }
+    let leaked_name: &'static str = Box::leak(name.clone().into_boxed_str());
+    let field_names: &'static [&'static str] = Box::leak(vec![leaked_name].into_boxed_slice());
+    interned.push((name, field_names));
+    field_names
 }
 
 impl<S, N2, E2, W2> Layer<S> for OpenTelemetryFmtLayer<S, N2, E2, W2>
@@ -132,7 +707,19 @@ where
     }
 
     fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
-        self.fmt_layer.on_new_span(attrs, id, ctx)
+        self.fmt_layer.on_new_span(attrs, id, ctx.clone());
+        // `OtelData` is populated by `OpenTelemetryLayer::on_new_span`, which
+        // runs before this layer's, so the ids are already resolvable here.
+        // Only cache once every field (notably the sampling-dependent ones)
+        // actually resolved; otherwise leave it to `cached_context_fields` to
+        // keep re-resolving until the sampling decision is known.
+        if let Some(values) = self.resolve_context_fields(id, &ctx) {
+            if values.iter().all(Option::is_some) {
+                if let Some(span) = ctx.span(id) {
+                    span.extensions_mut().insert(CachedContextFields(values));
+                }
+            }
+        }
     }
 
     fn max_level_hint(&self) -> Option<LevelFilter> {
@@ -152,40 +739,48 @@ where
     }
 
     fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
-        self.fmt_layer.on_event(event, ctx)
-    }
-
-    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
-        let span_context = Span::current().context();
-        let opentelemetry_span = span_context.span();
-        let ids = if opentelemetry_span.span_context().is_valid() {
-            Some((
-                opentelemetry_span.span_context().trace_id().to_string(),
-                opentelemetry_span.span_context().span_id().to_string(),
-            ))
+        let exception_restore = if self.exception_fields {
+            self.record_exception_fields(event, &ctx)
         } else {
             None
         };
+        let mut context_restore = None;
+        if self.injection_mode == InjectionMode::OnEvent {
+            if let Some(span) = ctx.event_span(event) {
+                let id = span.id();
+                if let Some(values) = self.cached_context_fields(&id, &ctx) {
+                    // Snapshot the span's formatted fields so these per-event
+                    // values are put back afterwards instead of piling up.
+                    let previous = span
+                        .extensions()
+                        .get::<FormattedFields<N2>>()
+                        .map(|fields| fields.fields.clone());
+                    self.record_context_fields(&id, &ctx, &values);
+                    context_restore = Some((id, previous));
+                }
+            }
+        }
+        self.fmt_layer.on_event(event, ctx.clone());
+        if let Some((id, previous)) = context_restore {
+            self.restore_formatted_fields(&id, previous, &ctx);
+        }
+        if let Some((id, previous)) = exception_restore {
+            self.restore_formatted_fields(&id, previous, &ctx);
+        }
+    }
 
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
         self.fmt_layer.on_enter(id, ctx.clone());
 
-        if let Some(ids) = ids {
-            let field_set = FieldSet::new(
-                self.field_names,
-                ctx.metadata(id)
-                    .expect("Metadata not found, this is a bug")
-                    .callsite(),
-            );
-            let mut it = field_set.iter();
-            let trace_field = it.next().expect("Trace field not found, this is a bug");
-            let span_field = it.next().expect("Span field not found, this is a bug");
-            let values = [
-                (&trace_field, Some(&ids.0 as &dyn Value)),
-                (&span_field, Some(&ids.1 as &dyn Value)),
-            ];
-            let values = field_set.value_set(&values);
-            let record = Record::new(&values);
-            self.fmt_layer.on_record(id, &record, ctx.clone());
+        if self.injection_mode == InjectionMode::OnEnter {
+            if let Some(values) = self.cached_context_fields(id, &ctx) {
+                self.record_context_fields(id, &ctx, &values);
+            }
+        }
+
+        if self.baggage.enabled {
+            let otel_cx = Span::current().context();
+            self.record_baggage(id, &otel_cx, &ctx);
         }
     }
 
@@ -198,6 +793,9 @@ where
     }
 
     fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        // No cache hand-off needed: `cached_context_fields` re-resolves from
+        // `OtelData` on a cache miss, so `new`'s first lookup just costs one
+        // extra resolve instead of reusing `old`'s cached values.
         self.fmt_layer.on_id_change(old, new, ctx)
     }
 
@@ -219,8 +817,43 @@ mod tests {
 
     use super::*;
 
+    /// A `MakeWriter` that captures formatted output into a shared buffer,
+    /// so tests can assert on the actual field values `FmtLayer` wrote
+    /// rather than only that nothing panicked.
+    #[derive(Clone)]
+    struct TestWriter(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl TestWriter {
+        fn new() -> Self {
+            Self(std::sync::Arc::new(std::sync::Mutex::new(Vec::new())))
+        }
+
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().expect("lock poisoned").clone())
+                .expect("output is not valid utf-8")
+        }
+    }
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().expect("lock poisoned").write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.lock().expect("lock poisoned").flush()
+        }
+    }
+
+    impl<'writer> tracing_subscriber::fmt::MakeWriter<'writer> for TestWriter {
+        type Writer = Self;
+
+        fn make_writer(&'writer self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
     #[test]
-    fn test_with_field_names() {
+    fn test_with_field_config() {
         let fmt_layer = fmt::layer()
             .with_thread_ids(true)
             .with_target(true)
@@ -235,7 +868,15 @@ mod tests {
 
         let opentelemetry_fmt_layer =
             OpenTelemetryFmtLayerBuilder::new(opentelemetry_layer, fmt_layer)
-                .with_field_names(&["custom.trace.id", "custom.span.id"])
+                .with_field_config(FieldConfig {
+                    trace_id: Some("custom.trace.id"),
+                    span_id: Some("custom.span.id"),
+                    trace_flags: Some("custom.trace.flags"),
+                    sampled: Some("custom.sampled"),
+                    parent_span_id: Some("custom.parent.span.id"),
+                    is_remote: Some("custom.is_remote"),
+                    traceparent: Some("traceparent"),
+                })
                 .build();
         tracing_subscriber::registry()
             .with(opentelemetry_fmt_layer)
@@ -246,4 +887,203 @@ mod tests {
             tracing::info!("in span1");
         });
     }
+
+    #[test]
+    fn test_with_baggage() {
+        let writer = TestWriter::new();
+        let fmt_layer = fmt::layer()
+            .with_target(true)
+            .with_ansi(false)
+            .with_writer(writer.clone());
+        let tracer = stdout::new_pipeline()
+            .with_writer(io::sink())
+            .install_simple();
+        let opentelemetry_layer = tracing_opentelemetry::layer().with_tracer(tracer);
+
+        let opentelemetry_fmt_layer =
+            OpenTelemetryFmtLayerBuilder::new(opentelemetry_layer, fmt_layer)
+                .with_baggage(true)
+                .with_baggage_prefix("baggage.")
+                .with_baggage_keys(vec!["tenant"])
+                .build();
+        let subscriber = tracing_subscriber::registry().with(opentelemetry_fmt_layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let cx = opentelemetry::Context::current().with_baggage(vec![
+                opentelemetry::KeyValue::new("tenant", "acme"),
+                opentelemetry::KeyValue::new("ignored", "nope"),
+            ]);
+            let _guard = cx.attach();
+
+            tracing::info_span!("span1").in_scope(|| {
+                tracing::info!("in span1");
+            });
+        });
+
+        let output = writer.contents();
+        assert!(
+            output.contains("baggage.tenant") && output.contains("acme"),
+            "expected allow-listed baggage entry in output, got: {output}"
+        );
+        assert!(
+            !output.contains("ignored") && !output.contains("nope"),
+            "baggage entry outside the allow-list leaked into output: {output}"
+        );
+    }
+
+    #[test]
+    fn test_with_fmt_filter() {
+        let writer = TestWriter::new();
+        let fmt_layer = fmt::layer()
+            .with_target(true)
+            .with_ansi(false)
+            .with_writer(writer.clone());
+        let tracer = stdout::new_pipeline()
+            .with_writer(io::sink())
+            .install_simple();
+        let opentelemetry_layer = tracing_opentelemetry::layer().with_tracer(tracer);
+
+        let opentelemetry_fmt_layer =
+            OpenTelemetryFmtLayerBuilder::new(opentelemetry_layer, fmt_layer)
+                .with_fmt_filter(LevelFilter::INFO)
+                .build();
+        let subscriber = tracing_subscriber::registry().with(opentelemetry_fmt_layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            // Exported to OpenTelemetry regardless, but filtered out of the
+            // console fmt output by the `INFO` fmt filter above.
+            tracing::debug_span!("span1").in_scope(|| {
+                tracing::debug!("filtered out of the console, not the trace");
+            });
+            tracing::info_span!("span2").in_scope(|| {
+                tracing::info!("reaches the console");
+            });
+        });
+
+        let output = writer.contents();
+        assert!(
+            !output.contains("filtered out of the console"),
+            "debug line should have been dropped by the INFO fmt filter: {output}"
+        );
+        assert!(
+            output.contains("reaches the console"),
+            "info line should have passed the INFO fmt filter: {output}"
+        );
+    }
+
+    #[test]
+    fn test_with_exception_fields() {
+        let writer = TestWriter::new();
+        let fmt_layer = fmt::layer()
+            .with_target(true)
+            .with_ansi(false)
+            .with_writer(writer.clone());
+        let tracer = stdout::new_pipeline()
+            .with_writer(io::sink())
+            .install_simple();
+        let opentelemetry_layer = tracing_opentelemetry::layer()
+            .with_exception_field_propagation(true)
+            .with_tracer(tracer);
+
+        let opentelemetry_fmt_layer =
+            OpenTelemetryFmtLayerBuilder::new(opentelemetry_layer, fmt_layer)
+                .with_exception_fields(true)
+                .build();
+        let subscriber = tracing_subscriber::registry().with(opentelemetry_fmt_layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let error = io::Error::new(io::ErrorKind::Other, "boom");
+            tracing::info_span!("span1").in_scope(|| {
+                tracing::error!(error = &error as &(dyn std::error::Error + 'static), "failed");
+                tracing::info!("next line");
+            });
+        });
+
+        let output = writer.contents();
+        let failed_line = output
+            .lines()
+            .find(|line| line.contains("failed"))
+            .expect("expected a log line for the error event");
+        assert!(
+            failed_line.contains("exception.message") && failed_line.contains("boom"),
+            "expected exception fields on the event that recorded the error: {failed_line}"
+        );
+
+        let next_line = output
+            .lines()
+            .find(|line| line.contains("next line"))
+            .expect("expected a log line for the unrelated event");
+        assert!(
+            !next_line.contains("exception.message"),
+            "exception fields leaked onto an unrelated later event in the same span: {next_line}"
+        );
+    }
+
+    #[test]
+    fn test_with_injection_mode_on_event() {
+        let writer = TestWriter::new();
+        let fmt_layer = fmt::layer()
+            .with_target(true)
+            .with_ansi(false)
+            .with_writer(writer.clone());
+        let tracer = stdout::new_pipeline()
+            .with_writer(io::sink())
+            .install_simple();
+        let opentelemetry_layer = tracing_opentelemetry::layer().with_tracer(tracer);
+
+        let opentelemetry_fmt_layer =
+            OpenTelemetryFmtLayerBuilder::new(opentelemetry_layer, fmt_layer)
+                .with_injection_mode(InjectionMode::OnEvent)
+                .build();
+        let subscriber = tracing_subscriber::registry().with(opentelemetry_fmt_layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("span1").in_scope(|| {
+                // Looked up from `span1`'s own (cached) `OtelData` values and
+                // recorded for every event, rather than once on span entry.
+                tracing::info!("first");
+                tracing::info!("second");
+            });
+        });
+
+        let output = writer.contents();
+        let first_line = output
+            .lines()
+            .find(|line| line.contains("first"))
+            .expect("expected a log line for the first event");
+        let second_line = output
+            .lines()
+            .find(|line| line.contains("second"))
+            .expect("expected a log line for the second event");
+
+        let trace_id = |line: &str| {
+            line.split("trace.id=")
+                .nth(1)
+                .and_then(|rest| rest.split_whitespace().next())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let span_id = |line: &str| {
+            line.split("span.id=")
+                .nth(1)
+                .and_then(|rest| rest.split_whitespace().next())
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        assert!(
+            !trace_id(first_line).is_empty(),
+            "expected trace.id to be resolved per event: {first_line}"
+        );
+        assert_eq!(
+            trace_id(first_line),
+            trace_id(second_line),
+            "both events belong to the same span, so trace.id should match: {output}"
+        );
+        assert_eq!(
+            span_id(first_line),
+            span_id(second_line),
+            "both events belong to the same span, so span.id should match: {output}"
+        );
+    }
 }